@@ -32,8 +32,57 @@ pub type Time = ::std::time::Duration;
 /// System-wide update type.
 pub type Diff = isize;
 
+/// Version of the wire format used to serialize `Command`/`Query` values.
+///
+/// A `Command` is built by one process and interpreted by another, potentially running a
+/// different build of this crate; a version mismatch between the two would otherwise
+/// silently corrupt execution. Bump this whenever `Command`, `Query`, or `Plan` change in
+/// a way that is not wire-compatible.
+pub const PLAN_FORMAT_VERSION: u32 = 1;
+
+/// A `Command`, wrapped with the wire format version it was serialized with.
+///
+/// This is what actually gets `bincode`-serialized and sent to a running system; the
+/// envelope lets a server reject a command from a skewed client with a typed error
+/// instead of failing to deserialize (or worse, misinterpreting) the `Plan` it contains.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Envelope<V: Datum> {
+    /// Format version the enclosed command was serialized with.
+    pub format_version: u32,
+    /// The command itself.
+    pub command: Command<V>,
+}
+
+/// An error encountered while decoding a `Command` envelope.
+#[derive(Debug)]
+pub enum EnvelopeError {
+    /// The envelope's `format_version` does not match `PLAN_FORMAT_VERSION`.
+    VersionMismatch {
+        /// Version found in the envelope.
+        found: u32,
+        /// Version this build expects.
+        expected: u32,
+    },
+    /// The envelope's bytes could not be deserialized at all.
+    Decode(bincode::Error),
+}
+
+impl ::std::fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            EnvelopeError::VersionMismatch { found, expected } =>
+                write!(f, "format version mismatch: found {}, expected {}", found, expected),
+            EnvelopeError::Decode(err) =>
+                write!(f, "failed to decode command envelope: {}", err),
+        }
+    }
+}
+
+impl ::std::error::Error for EnvelopeError { }
+
 use std::hash::Hash;
 use std::fmt::Debug;
+use std::collections::BTreeMap;
 use serde::{Serialize, Deserialize};
 
 /// Types capable of use as data in interactive.
@@ -73,12 +122,21 @@ pub struct Query<V: Datum> {
     pub imports: Vec<(Plan<V>, Vec<usize>)>,
     /// A list of arrangements to publish.
     pub publish: Vec<(Plan<V>, Vec<usize>)>,
+    /// Constant bindings for this query's free parameters (see `Plan::Parameter`), checked
+    /// for completeness by `check_bindings` and substituted into the plan by
+    /// `substitute_bindings`.
+    pub bindings: BTreeMap<String, Vec<V>>,
 }
 
 impl<V: Datum> Query<V> {
     /// Creates a new, empty query.
     pub fn new() -> Self {
-        Query { rules: Vec::new(), imports: Vec::new(), publish: Vec::new(), }
+        Query {
+            rules: Vec::new(),
+            imports: Vec::new(),
+            publish: Vec::new(),
+            bindings: BTreeMap::new(),
+        }
     }
     /// Adds a rule to an existing query.
     pub fn add_rule(mut self, rule: Rule<V>) -> Self {
@@ -95,8 +153,82 @@ impl<V: Datum> Query<V> {
         self.publish.push((plan, keys));
         self
     }
+    /// Binds a named parameter to a constant value for this installation of the query.
+    pub fn with_binding(mut self, name: String, value: Vec<V>) -> Self {
+        self.bindings.insert(name, value);
+        self
+    }
+    /// Collects the names of every free parameter referenced by this query's rules,
+    /// imports, and published plans.
+    pub fn parameters(&self) -> ::std::collections::HashSet<String> {
+        let mut out = ::std::collections::HashSet::new();
+        for rule in &self.rules {
+            rule.plan.collect_parameters(&mut out);
+        }
+        for (plan, _keys) in &self.imports {
+            plan.collect_parameters(&mut out);
+        }
+        for (plan, _keys) in &self.publish {
+            plan.collect_parameters(&mut out);
+        }
+        out
+    }
+    /// Checks that `bindings` supplies a value for every name `parameters` returns, so a
+    /// missing binding is reported as a typed error before the dataflow is installed rather
+    /// than discovered deep inside a running operator.
+    pub fn check_bindings(&self) -> Result<(), BindingError> {
+        for name in self.parameters() {
+            if !self.bindings.contains_key(&name) {
+                return Err(BindingError::Unbound { parameter: name });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<V: Datum + Clone> Query<V> {
+    /// Checks that every free parameter is bound, then replaces each `Plan::Parameter` leaf
+    /// across `rules`, `imports`, and `publish` with the constant it's bound to, producing a
+    /// query that can be installed without any further binding lookups. The query is taken
+    /// by value; the returned query keeps its `bindings` but has no `Parameter` leaves left,
+    /// so binding it again and substituting again does not change its plans.
+    pub fn substitute_bindings(mut self) -> Result<Self, BindingError> {
+        self.check_bindings()?;
+        let bindings = self.bindings.clone();
+        for rule in &mut self.rules {
+            rule.plan = rule.plan.substitute(&bindings);
+        }
+        for (plan, _keys) in &mut self.imports {
+            *plan = plan.substitute(&bindings);
+        }
+        for (plan, _keys) in &mut self.publish {
+            *plan = plan.substitute(&bindings);
+        }
+        Ok(self)
+    }
 }
 
+/// An error discovered while resolving a `Query`'s parameter bindings at install time.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BindingError {
+    /// A plan referenced a parameter that has no entry in `Query::bindings`.
+    Unbound {
+        /// Name of the unbound parameter.
+        parameter: String,
+    },
+}
+
+impl ::std::fmt::Display for BindingError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            BindingError::Unbound { parameter } =>
+                write!(f, "parameter `{}` has no binding", parameter),
+        }
+    }
+}
+
+impl ::std::error::Error for BindingError { }
+
 impl<V: Datum> Query<V> {
     /// Converts the query into a command.
     pub fn into_command(self) -> Command<V> {
@@ -104,6 +236,38 @@ impl<V: Datum> Query<V> {
     }
 }
 
+impl<V: Datum> Command<V> {
+    /// The wire format version a command should be serialized with.
+    ///
+    /// Every variant currently shares the one crate-wide `PLAN_FORMAT_VERSION`; this takes
+    /// `&self` so a future variant could report a version of its own without changing the
+    /// call site in `encode_envelope`. `decode_envelope` does not call this; it compares the
+    /// envelope's version against `PLAN_FORMAT_VERSION` directly.
+    pub fn format_version(&self) -> u32 {
+        PLAN_FORMAT_VERSION
+    }
+}
+
+impl<V: Datum + Serialize + for<'de> Deserialize<'de>> Command<V> {
+    /// Wraps the command in an `Envelope` and serializes it with `bincode`.
+    pub fn encode_envelope(self) -> Result<Vec<u8>, bincode::Error> {
+        let envelope = Envelope { format_version: self.format_version(), command: self };
+        bincode::serialize(&envelope)
+    }
+    /// Deserializes a `bincode`-encoded `Envelope`, rejecting one whose `format_version`
+    /// does not match `PLAN_FORMAT_VERSION` rather than interpreting its `Plan`.
+    pub fn decode_envelope(bytes: &[u8]) -> Result<Command<V>, EnvelopeError> {
+        // `format_version` is the envelope's first field, so it can be read on its own
+        // before the command (whose layout may differ across versions) is decoded.
+        let found: u32 = bincode::deserialize(bytes).map_err(EnvelopeError::Decode)?;
+        if found != PLAN_FORMAT_VERSION {
+            return Err(EnvelopeError::VersionMismatch { found, expected: PLAN_FORMAT_VERSION });
+        }
+        let envelope: Envelope<V> = bincode::deserialize(bytes).map_err(EnvelopeError::Decode)?;
+        Ok(envelope.command)
+    }
+}
+
 /// Definition of a single collection.
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Rule<V: Datum> {
@@ -119,3 +283,164 @@ impl<V: Datum> Rule<V> {
         Query::new().add_rule(self)
     }
 }
+
+/// The shape of a relation: how many columns each of its rows has.
+///
+/// `Datum` values in this crate are homogeneous (every column has type `V`), so unlike a
+/// richer schema system there is no per-column type to check beyond arity: two relations
+/// are compatible exactly when they have the same number of columns.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct RelationType {
+    /// Number of columns in the relation.
+    pub arity: usize,
+}
+
+impl RelationType {
+    /// Creates a relation type of the given arity.
+    pub fn new(arity: usize) -> Self {
+        RelationType { arity }
+    }
+}
+
+/// An inconsistency discovered while validating a `Query` before it is installed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TypeError {
+    /// Two rules in the same query share a name.
+    DuplicateRule {
+        /// The repeated rule name.
+        name: String,
+    },
+    /// A `Plan::Get` referenced a rule name that hasn't been defined (or comes later).
+    UnboundRelation {
+        /// The name that couldn't be resolved.
+        name: String,
+    },
+    /// A `Plan::Constant`'s rows don't all have the same number of columns.
+    RaggedConstant {
+        /// Arity of the constant's first row.
+        expected: usize,
+        /// Arity of the row that disagreed with it.
+        found: usize,
+    },
+}
+
+impl ::std::fmt::Display for TypeError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            TypeError::DuplicateRule { name } =>
+                write!(f, "rule `{}` is defined more than once", name),
+            TypeError::UnboundRelation { name } =>
+                write!(f, "no rule named `{}` is defined", name),
+            TypeError::RaggedConstant { expected, found } =>
+                write!(f, "constant has rows of arity {} and {}", expected, found),
+        }
+    }
+}
+
+impl ::std::error::Error for TypeError { }
+
+impl<V: Datum> Query<V> {
+    /// Checks the query for inconsistencies before it is turned into a running dataflow.
+    ///
+    /// Walks the rules in order, type-checking each one's plan against the relation types
+    /// of the rules already seen and rejecting a rule that repeats an earlier name. Plan
+    /// nodes that don't exist yet (join, projection, filter, union) aren't type-checked
+    /// here because there's nothing to check them against; this currently covers exactly
+    /// what `Plan::type_of` covers: `Get`, `Constant`, and `Parameter`.
+    pub fn validate(&self) -> Result<(), TypeError> {
+        let mut env = ::std::collections::HashMap::new();
+        for rule in &self.rules {
+            if env.contains_key(&rule.name) {
+                return Err(TypeError::DuplicateRule { name: rule.name.clone() });
+            }
+            let relation_type = rule.plan.type_of(&env)?;
+            env.insert(rule.name.clone(), relation_type);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelope_round_trips() {
+        let command = Query::<usize>::new().into_command();
+        let bytes = command.clone().encode_envelope().unwrap();
+        let decoded = Command::<usize>::decode_envelope(&bytes).unwrap();
+        assert_eq!(command, decoded);
+    }
+
+    #[test]
+    fn envelope_rejects_version_mismatch() {
+        let query = Query::<usize>::new();
+        let envelope = Envelope { format_version: PLAN_FORMAT_VERSION + 1, command: query.into_command() };
+        let bytes = bincode::serialize(&envelope).unwrap();
+        match Command::<usize>::decode_envelope(&bytes) {
+            Err(EnvelopeError::VersionMismatch { found, expected }) => {
+                assert_eq!(found, PLAN_FORMAT_VERSION + 1);
+                assert_eq!(expected, PLAN_FORMAT_VERSION);
+            }
+            other => panic!("expected a version mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn validate_rejects_duplicate_rule_names() {
+        let query = Query::<usize>::new()
+            .add_rule(Rule { name: "a".into(), plan: Plan::Constant(vec![]) })
+            .add_rule(Rule { name: "a".into(), plan: Plan::Constant(vec![]) });
+        match query.validate() {
+            Err(TypeError::DuplicateRule { name }) => assert_eq!(name, "a"),
+            other => panic!("expected a duplicate rule error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn validate_rejects_unbound_relation() {
+        let query = Query::<usize>::new()
+            .add_rule(Rule { name: "a".into(), plan: Plan::Get("missing".into()) });
+        match query.validate() {
+            Err(TypeError::UnboundRelation { name }) => assert_eq!(name, "missing"),
+            other => panic!("expected an unbound relation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn check_bindings_rejects_unbound_parameter() {
+        let query = Query::<usize>::new()
+            .add_rule(Rule { name: "a".into(), plan: Plan::Parameter("threshold".into()) });
+        match query.check_bindings() {
+            Err(BindingError::Unbound { parameter }) => assert_eq!(parameter, "threshold"),
+            other => panic!("expected an unbound parameter error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn check_bindings_accepts_bound_parameter() {
+        let query = Query::<usize>::new()
+            .add_rule(Rule { name: "a".into(), plan: Plan::Parameter("threshold".into()) })
+            .with_binding("threshold".into(), vec![0]);
+        assert_eq!(query.check_bindings(), Ok(()));
+    }
+
+    #[test]
+    fn substitute_bindings_replaces_parameter_with_constant() {
+        let query = Query::<usize>::new()
+            .add_rule(Rule { name: "a".into(), plan: Plan::Parameter("threshold".into()) })
+            .with_binding("threshold".into(), vec![1, 2, 3]);
+        let installable = query.substitute_bindings().unwrap();
+        assert_eq!(installable.rules[0].plan, Plan::Constant(vec![vec![1], vec![2], vec![3]]));
+    }
+
+    #[test]
+    fn substitute_bindings_rejects_unbound_parameter() {
+        let query = Query::<usize>::new()
+            .add_rule(Rule { name: "a".into(), plan: Plan::Parameter("threshold".into()) });
+        match query.substitute_bindings() {
+            Err(BindingError::Unbound { parameter }) => assert_eq!(parameter, "threshold"),
+            other => panic!("expected an unbound parameter error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Query plans: descriptions of how to derive a collection from its inputs.
+//!
+//! This is a minimal subset of the plan algebra the interactive system is meant to grow
+//! into. Join, projection, filter, and union nodes are not implemented yet; what's here is
+//! just enough for a rule to reference another rule by name (`Get`), to describe an inline
+//! constant collection (`Constant`), or to stand in for a value bound later (`Parameter`).
+
+use std::collections::{BTreeMap, HashMap};
+use serde::{Serialize, Deserialize};
+
+use crate::{Datum, RelationType, TypeError};
+
+/// A plan describing how to construct a collection.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Plan<V: Datum> {
+    /// References another rule in the same query by name.
+    Get(String),
+    /// An inline constant collection; each inner `Vec<V>` is one row.
+    Constant(Vec<Vec<V>>),
+    /// A named parameter, substituted with a `Query`'s `bindings` at install time.
+    Parameter(String),
+}
+
+impl<V: Datum> Plan<V> {
+    /// Computes the `RelationType` of this plan's output against `env`, the relation types
+    /// of the rules already checked, or the first inconsistency found while doing so.
+    pub fn type_of(&self, env: &HashMap<String, RelationType>) -> Result<RelationType, TypeError> {
+        match self {
+            Plan::Get(name) => {
+                env.get(name)
+                    .cloned()
+                    .ok_or_else(|| TypeError::UnboundRelation { name: name.clone() })
+            }
+            Plan::Constant(rows) => {
+                let arity = rows.first().map(|row| row.len()).unwrap_or(0);
+                for row in rows {
+                    if row.len() != arity {
+                        return Err(TypeError::RaggedConstant { expected: arity, found: row.len() });
+                    }
+                }
+                Ok(RelationType::new(arity))
+            }
+            // A parameter is bound to a flat `Vec<V>` in `Query::bindings`, i.e. one column.
+            Plan::Parameter(_) => Ok(RelationType::new(1)),
+        }
+    }
+
+    /// Collects the names of every `Parameter` this plan references into `out`.
+    pub fn collect_parameters(&self, out: &mut ::std::collections::HashSet<String>) {
+        if let Plan::Parameter(name) = self {
+            out.insert(name.clone());
+        }
+    }
+}
+
+impl<V: Datum + Clone> Plan<V> {
+    /// Replaces each `Parameter` leaf with a `Constant` built from its binding, one row per
+    /// bound element (consistent with the arity-1 `RelationType` `type_of` gives a
+    /// `Parameter`). Panics if `name` has no entry in `bindings`; callers are expected to
+    /// have checked that already with `Query::check_bindings`.
+    pub fn substitute(&self, bindings: &BTreeMap<String, Vec<V>>) -> Plan<V> {
+        match self {
+            Plan::Get(name) => Plan::Get(name.clone()),
+            Plan::Constant(rows) => Plan::Constant(rows.clone()),
+            Plan::Parameter(name) => {
+                let value = bindings.get(name)
+                    .unwrap_or_else(|| panic!("parameter `{}` has no binding", name));
+                Plan::Constant(value.iter().cloned().map(|v| vec![v]).collect())
+            }
+        }
+    }
+}